@@ -12,12 +12,16 @@ mod sanitizer;
     about = "A tool for optimize pants jvm dependencies"
 )]
 pub struct Config {
-    /// Full path to Pants 'dep-usage.jvm' report file in Json format.
-    /// You should create it before using this tool like this
-    /// `./pants -q dep-usage.jvm --no-summary src/:: > deps.json`
-    /// and provide full path to this file.
-    #[structopt(short, long, parse(from_os_str), default_value = "deps.json")]
-    report_file: PathBuf,
+    /// Full path to a Pants 'dep-usage.jvm' report file in Json format, for offline use.
+    /// When omitted (the default), the tool invokes Pants itself via `--pants-binary` against
+    /// `--prefix`, so the report always reflects the current state of the tree.
+    #[structopt(short, long, parse(from_os_str))]
+    report_file: Option<PathBuf>,
+
+    /// Path to the Pants binary to invoke for a live `dep-usage.jvm` report when
+    /// `--report-file` is not given.
+    #[structopt(long, default_value = "./pants")]
+    pants_binary: String,
 
     /// Applies any action only for modules that start with this include_prefix.
     #[structopt(short, long, default_value = "src/scala/")]
@@ -27,6 +31,45 @@ pub struct Config {
     #[structopt(short, long, default_value = "#skip-sanitize")]
     skip_marker: String,
 
+    /// Copy the original BUILD file to a sibling BUILD.bak before atomically replacing it.
+    #[structopt(long)]
+    backup: bool,
+
+    /// When adding undeclared dependencies, keep existing entries in their original order (no
+    /// re-sort) and only append the new ones, instead of re-sorting the whole
+    /// `dependencies=[...]` block. Produces a smaller diff at the cost of the block no longer
+    /// being kept alphabetically sorted. Entries are still normalized as in every mode: double
+    /// quotes become single quotes and a missing trailing comma is added.
+    #[structopt(long)]
+    preserve_order: bool,
+
+    /// Check mode: don't write any BUILD file, just print a diff of what would change and
+    /// exit with a non-zero status if anything is not sanitized. Useful for gating CI on
+    /// `unused fix`, `undeclared fix` and `sort` without mutating the tree.
+    #[structopt(long)]
+    check: bool,
+
+    /// Dry-run mode: don't write any BUILD file, just print a diff of what would change, same
+    /// as `--check`, but always exit with status 0. Useful for reviewing proposed edits by hand
+    /// before committing to them, without failing a script that merely wants a preview.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Maximum number of threads to use when scanning and fixing BUILD files in parallel.
+    /// Defaults to rayon's own heuristic (one thread per CPU) when unset.
+    #[structopt(long)]
+    jobs: Option<usize>,
+
+    /// Write a machine-readable summary of the run (per-module addresses removed/added, totals,
+    /// timestamp) to this path, so CI can track dependency-hygiene trends across commits. Format
+    /// is controlled by `--summary-format`.
+    #[structopt(long, parse(from_os_str))]
+    summary_file: Option<PathBuf>,
+
+    /// Format to write `--summary-file` in: "json" or "csv".
+    #[structopt(long, default_value = "json")]
+    summary_format: String,
+
     #[structopt(subcommand)]
     cmd: Command,
 }
@@ -2,7 +2,6 @@
 
 use crate::sanitizer::deps_manager;
 use regex::Regex;
-use std::collections::BTreeSet;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
@@ -10,7 +9,15 @@ use std::fs;
 use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::PathBuf;
+use std::process;
 use std::string::ToString;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Serializes stdout output across rayon worker threads: shared by `print_build_diff` here and
+/// by the per-module "removed"/"added"/"sorted" lines in `crate::sanitizer`, so `--check` diffs
+/// and summary lines from different BUILD files never interleave.
+pub(crate) static STDOUT_LOCK: Mutex<()> = Mutex::new(());
 
 /// Representation for Pants address.
 #[derive(Clone, PartialOrd, Ord, PartialEq, Eq)]
@@ -56,51 +63,124 @@ impl Debug for Address {
     }
 }
 
-/// Finds BUILD file and removes lines with unused dependencies, returns number of removed lines.
+/// Scopes dependency edits to exactly one named target within a BUILD file that defines several
+/// (e.g. a `scala_library` `cc` followed by a `scala_library` `cc-test`). A target invocation is
+/// assumed to be a top-level (non-indented) statement like `scala_library(`; seeing a new one
+/// ends whatever target we were previously inside, so a later target's `dependencies=[...]` is
+/// never mistaken for the one we're editing. Replaces the old `inside_module_section` heuristic,
+/// which latched to `true` on the first matching `name=` line and never reset, so every
+/// subsequent target in the file was treated as in-scope too - and which matched `name=` by
+/// substring, so `module_name = "cc"` also matched a target named `cc-test`.
+struct TargetScope {
+    inside: bool,
+    seen_target: bool,
+}
+
+impl TargetScope {
+    fn new(inside_by_default: bool) -> Self {
+        TargetScope {
+            inside: inside_by_default,
+            seen_target: false,
+        }
+    }
+
+    /// Feeds one line, returning whether it falls inside the scoped target's section.
+    fn feed_line(&mut self, line: &str, module_name: &str) -> bool {
+        if is_target_start(line) {
+            if self.seen_target {
+                self.inside = false;
+            }
+            self.seen_target = true;
+        }
+
+        if matches_target_name(line, module_name) {
+            self.inside = true;
+        }
+
+        self.inside
+    }
+}
+
+/// Whether `line` looks like the start of a top-level Pants target invocation, e.g.
+/// `scala_library(`: not indented, and opening a call. Used to know when the previously matched
+/// target's section has ended.
+fn is_target_start(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    !trimmed.is_empty()
+        && trimmed.len() == line.len()
+        && !trimmed.starts_with('#')
+        && trimmed
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_alphabetic() || c == '_')
+        && trimmed.contains('(')
+}
+
+/// Whether `line` is a `name=...` line declaring exactly `module_name`, e.g. `name="cc"` for
+/// `module_name = "cc"` - an exact quoted match, not a substring one, so `"cc"` doesn't also
+/// match a `name="cc-test"` line.
+fn matches_target_name(line: &str, module_name: &str) -> bool {
+    line.contains(&format!("name=\"{}\"", module_name))
+        || line.contains(&format!("name='{}'", module_name))
+}
+
+/// Finds BUILD file and removes lines with unused dependencies, returns the number of removed
+/// lines and the paths of the BUILD files that changed (or would change, in `--check` mode).
 pub fn remove_deps(
     module: &Address,
     deps: &Vec<Address>,
     skip_marker: &str,
-) -> Result<i32, Box<dyn Error>> {
+    backup: bool,
+    check: bool,
+) -> Result<(i32, Vec<PathBuf>), Box<dyn Error>> {
     let mut counter = 0;
+    let mut changed = Vec::new();
 
     for entry in fs::read_dir(&module.folder)? {
         let entry = entry?;
         if entry.file_name() == "BUILD" {
-            let mut inside_module_section = module.is_simple();
-            counter += run_for_block(
+            let mut scope = TargetScope::new(module.is_simple());
+            let (edited, file) = run_for_block(
                 entry.path(),
                 |line| {
-                    if line.contains("name=") && line.contains(&module.module_name) {
-                        inside_module_section = true;
-                    }
-                    inside_module_section && deps_manager::deps_block_start(line)
+                    scope.feed_line(line, &module.module_name)
+                        && deps_manager::deps_block_start(line)
                 },
-                deps_manager::block_ends,
-                |lines| {
-                    lines
-                        .into_iter()
-                        .filter(|line| {
-                            line.contains(skip_marker)
-                                || !deps.iter().any(|target| target.match_line(&line))
-                        })
-                        .collect()
+                |block: DepsBlock| {
+                    warn_unmatched(&block, deps);
+                    block.filter(|line| {
+                        line.contains(skip_marker)
+                            || !deps.iter().any(|target| target.match_line(line))
+                    })
                 },
                 skip_marker,
+                backup,
+                check,
             )
             .unwrap();
+            counter += edited;
+            changed.extend(file);
         }
     }
-    Ok(counter.abs())
+    Ok((counter.abs(), changed))
 }
 
-/// Finds a BUILD file and inserts lines with undeclared dependencies, returns number of inserted lines.
+/// Finds a BUILD file and inserts lines with undeclared dependencies, returns the number of
+/// inserted lines and the paths of the BUILD files that changed (or would change, in
+/// `--check` mode). When `preserve_order` is set, existing entries keep their original order
+/// (no re-sort) and only the new lines are appended; otherwise the whole block is re-sorted as
+/// usual. In both modes `run_for_block` normalizes entries (double quotes to single quotes, a
+/// missing trailing comma added), so existing entries are not kept byte-for-byte.
 pub fn add_deps(
     module: &Address,
     deps: Vec<Address>,
     skip_marker: &str,
-) -> Result<i32, Box<dyn Error>> {
+    backup: bool,
+    check: bool,
+    preserve_order: bool,
+) -> Result<(i32, Vec<PathBuf>), Box<dyn Error>> {
     let mut counter = 0;
+    let mut changed = Vec::new();
 
     for entry in fs::read_dir(&module.folder)? {
         let entry = entry?;
@@ -108,34 +188,375 @@ pub fn add_deps(
         if entry.file_name() == "BUILD" {
             // add undeclared and sort
 
-            let mut inside_module_section = module.is_simple();
+            let mut scope = TargetScope::new(module.is_simple());
 
-            counter += run_for_block(
+            let (edited, file) = run_for_block(
                 entry.path(),
                 |line: &str| {
-                    if line.contains("name=") && line.contains(&module.module_name) {
-                        inside_module_section = true;
-                    }
-
-                    inside_module_section && deps_manager::deps_block_start(line)
+                    scope.feed_line(line, &module.module_name)
+                        && deps_manager::deps_block_start(line)
                 },
-                deps_manager::block_ends,
-                |mut file_deps: BTreeSet<String>| {
+                |block: DepsBlock| {
                     // add undeclared deps to deps from file
                     let deps_iter = deps
                         .clone()
                         .into_iter()
                         .map(|dep| format!("        '{}',", dep.as_str()));
 
-                    file_deps.extend(deps_iter);
-                    file_deps
+                    if preserve_order {
+                        block.extend_preserving_order(deps_iter)
+                    } else {
+                        block.extend_sorted(deps_iter)
+                    }
                 },
                 skip_marker,
+                backup,
+                check,
             )
             .unwrap();
+            counter += edited;
+            changed.extend(file);
+        }
+    }
+    Ok((counter, changed))
+}
+
+/// One dependency line together with any full-line comments that directly preceded it in the
+/// source, kept glued together through sorting and filtering so a comment documenting a target
+/// never detaches from it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct DepEntry {
+    leading_comments: Vec<String>,
+    dep_line: String,
+}
+
+/// An ordered `dependencies=[...]`/`exports=[...]` block: the `DepEntry`s plus any comments left
+/// over at the end of the block with no dependency line following them (e.g. a trailing
+/// separator), which are always re-emitted last, unaffected by how the entries are sorted or
+/// filtered.
+#[derive(Clone, Debug, Default)]
+pub struct DepsBlock {
+    entries: Vec<DepEntry>,
+    trailing_comments: Vec<String>,
+}
+
+impl DepsBlock {
+    fn new() -> Self {
+        DepsBlock::default()
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Sorts entries by their dependency line, keeping each one's leading comments glued to it.
+    pub fn sorted(mut self) -> Self {
+        self.entries.sort_by(|a, b| a.dep_line.cmp(&b.dep_line));
+        self
+    }
+
+    /// Keeps only the entries whose dependency line satisfies `predicate`, dropping their
+    /// leading comments along with them.
+    pub fn filter<F: Fn(&str) -> bool>(mut self, predicate: F) -> Self {
+        self.entries.retain(|entry| predicate(&entry.dep_line));
+        self
+    }
+
+    /// Appends `dep_lines` as new entries with no leading comments, then re-sorts the block.
+    /// Lines already present in the block (e.g. a stale report re-adding a dep that's already
+    /// declared) are skipped instead of being duplicated.
+    pub fn extend_sorted<I: IntoIterator<Item = String>>(mut self, dep_lines: I) -> Self {
+        let existing: Vec<String> = self.entries.iter().map(|entry| entry.dep_line.clone()).collect();
+        let new_entries = dep_lines
+            .into_iter()
+            .filter(|dep_line| !existing.contains(dep_line))
+            .map(|dep_line| DepEntry {
+                leading_comments: Vec::new(),
+                dep_line,
+            });
+        self.entries.extend(new_entries);
+        self.sorted()
+    }
+
+    /// Appends `dep_lines` as new entries with no leading comments, leaving the existing entries'
+    /// order and formatting untouched - unlike `extend_sorted`, which re-sorts the whole block.
+    /// Lines already present are skipped instead of duplicated; genuinely new lines are appended
+    /// in sorted order, so repeated runs produce a stable diff.
+    pub fn extend_preserving_order<I: IntoIterator<Item = String>>(mut self, dep_lines: I) -> Self {
+        let existing: Vec<String> = self.entries.iter().map(|entry| entry.dep_line.clone()).collect();
+        let mut new_lines: Vec<String> = dep_lines
+            .into_iter()
+            .filter(|dep_line| !existing.contains(dep_line))
+            .collect();
+        new_lines.sort();
+        self.entries.extend(new_lines.into_iter().map(|dep_line| DepEntry {
+            leading_comments: Vec::new(),
+            dep_line,
+        }));
+        self
+    }
+
+    /// Flattens the block back into BUILD file lines: each entry's leading comments then its
+    /// dependency line, in entry order, followed by any trailing comments.
+    fn into_lines(self) -> Vec<String> {
+        let mut result = Vec::new();
+        for entry in self.entries {
+            result.extend(entry.leading_comments);
+            result.push(entry.dep_line);
+        }
+        result.extend(self.trailing_comments);
+        result
+    }
+}
+
+/// Warns about any `target` in `deps` that `match_line` couldn't find anywhere in `block` -
+/// a stale report, a renamed target, or a `match_line` miss - by suggesting the closest real
+/// address actually present in the block, if one is close enough to plausibly be a typo.
+fn warn_unmatched(block: &DepsBlock, deps: &[Address]) {
+    let present: Vec<&str> = block
+        .entries
+        .iter()
+        .filter_map(|entry| extract_address(&entry.dep_line))
+        .collect();
+
+    for target in deps {
+        if block
+            .entries
+            .iter()
+            .any(|entry| target.match_line(&entry.dep_line))
+        {
+            continue;
+        }
+
+        let target_str = target.as_str();
+        let closest = present
+            .iter()
+            .min_by_key(|addr| levenshtein_distance(&target_str, addr));
+
+        if let Some(closest) = closest {
+            let distance = levenshtein_distance(&target_str, closest);
+            let len = target_str.chars().count();
+            if distance <= 2 || distance * 5 <= len {
+                let _guard = STDOUT_LOCK.lock().unwrap();
+                println!(
+                    "{:?}: '{}' not found in BUILD file, did you mean '{}'?",
+                    target, target_str, closest
+                );
+            }
+        }
+    }
+}
+
+/// Pulls the quoted address out of a dependency line, e.g. `'foo/bar:baz',` -> `foo/bar:baz`.
+fn extract_address(dep_line: &str) -> Option<&str> {
+    let start = dep_line.find(|c| c == '\'' || c == '"')?;
+    let rest = &dep_line[start + 1..];
+    let end = rest.find(|c| c == '\'' || c == '"')?;
+    Some(&rest[..end])
+}
+
+/// Standard two-row dynamic-programming edit distance between `a` and `b`.
+fn levenshtein_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0usize; b.len() + 1];
+
+    for i in 0..a.len() {
+        cur[0] = i + 1;
+        for j in 0..b.len() {
+            let cost = if a[i] != b[j] { 1 } else { 0 };
+            cur[j + 1] = (prev[j + 1] + 1).min(cur[j] + 1).min(prev[j] + cost);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[b.len()]
+}
+
+/// Tracks `(`/`[`/`{` nesting depth across the lines of a block, ignoring brackets inside
+/// string literals and after a `#` comment marker, so a block is only considered closed once
+/// its own opening bracket has a matching close - not on the first stray `]` (e.g. from a
+/// nested list, a list comprehension, or a second target's block).
+struct BracketDepth {
+    depth: i32,
+    in_string: Option<char>,
+}
+
+impl BracketDepth {
+    fn new() -> Self {
+        BracketDepth {
+            depth: 0,
+            in_string: None,
+        }
+    }
+
+    /// Feeds one line, returning the nesting depth after it was scanned.
+    fn feed_line(&mut self, line: &str) -> i32 {
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if let Some(quote) = self.in_string {
+                if c == '\\' {
+                    chars.next();
+                    continue;
+                }
+                if c == quote {
+                    self.in_string = None;
+                }
+                continue;
+            }
+
+            match c {
+                '#' => break, // rest of the line is a comment
+                '"' | '\'' => self.in_string = Some(c),
+                '(' | '[' | '{' => self.depth += 1,
+                ')' | ']' | '}' => self.depth -= 1,
+                _ => {}
+            }
+        }
+        self.depth
+    }
+}
+
+/// Handles a `dependencies=[...]`/`exports=[...]` block that opens and closes on the same line
+/// (e.g. `dependencies=["a:b"],`): parses the inline entries, runs `block_fn` over them just
+/// like a multi-line block, then expands the result back out one entry per line so the block
+/// stays editable on future runs. Falls back to the line unchanged if no brackets or no entries
+/// are found (e.g. an empty `dependencies=[]`).
+fn run_inline_block<F2: FnMut(DepsBlock) -> DepsBlock>(
+    line: &str,
+    block_fn: &mut F2,
+    line_edited: &mut i32,
+) -> Vec<String> {
+    let (prefix, inner, suffix) = match split_inline_block(line) {
+        Some(parts) => parts,
+        None => return vec![line.to_string()],
+    };
+
+    let entries: Vec<DepEntry> = split_inline_entries(inner)
+        .into_iter()
+        .map(|token| DepEntry {
+            leading_comments: Vec::new(),
+            dep_line: format!("        {},", token.replace('"', "'")),
+        })
+        .collect();
+
+    if entries.is_empty() {
+        return vec![line.to_string()];
+    }
+
+    let before = entries.len();
+    let result = block_fn(DepsBlock {
+        entries,
+        trailing_comments: Vec::new(),
+    });
+    *line_edited = result.len() as i32 - before as i32;
+
+    let mut out = vec![prefix.to_string()];
+    out.extend(result.into_lines());
+    out.push(suffix.to_string());
+    out
+}
+
+/// Splits a line whose bracketed list opens and closes on itself, e.g.
+/// `    dependencies=["a:b"],`, into `(prefix ending in the opening bracket, inner content,
+/// suffix starting at the matching closing bracket)`. Returns `None` if no bracket is found.
+///
+/// The close is the bracket that returns depth to the level it was at right before the `[` we
+/// picked as `open_idx` - not merely the first bracket of any kind that brings the overall depth
+/// back to zero. Otherwise a line like `scala_library(name="x", dependencies=["a:b"])` would
+/// match the target invocation's own closing `)` instead of the dependency list's `]`, since the
+/// enclosing `(` already put depth at 1 before the `[` is ever seen.
+fn split_inline_block(line: &str) -> Option<(&str, &str, &str)> {
+    let mut depth = 0i32;
+    let mut in_string: Option<char> = None;
+    let mut open_idx = None;
+    let mut close_idx = None;
+    let mut target_depth = 0i32;
+
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if let Some(quote) = in_string {
+            if c == '\\' {
+                chars.next();
+                continue;
+            }
+            if c == quote {
+                in_string = None;
+            }
+            continue;
+        }
+
+        match c {
+            '#' => break,
+            '"' | '\'' => in_string = Some(c),
+            '(' | '[' | '{' => {
+                if c == '[' && open_idx.is_none() {
+                    open_idx = Some(i);
+                    target_depth = depth;
+                }
+                depth += 1;
+            }
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if open_idx.is_some() && close_idx.is_none() && depth == target_depth {
+                    close_idx = Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match (open_idx, close_idx) {
+        (Some(open), Some(close)) => {
+            Some((&line[..=open], &line[open + 1..close], &line[close..]))
+        }
+        _ => None,
+    }
+}
+
+/// Splits a bracketed list's inner content on top-level commas (ignoring commas inside quoted
+/// strings), trimming whitespace and dropping empty segments (e.g. a trailing comma before `]`).
+fn split_inline_entries(inner: &str) -> Vec<&str> {
+    let mut depth = 0i32;
+    let mut in_string: Option<char> = None;
+    let mut start = 0;
+    let mut result = Vec::new();
+
+    let mut chars = inner.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if let Some(quote) = in_string {
+            if c == '\\' {
+                chars.next();
+                continue;
+            }
+            if c == quote {
+                in_string = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => in_string = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
         }
     }
-    Ok(counter)
+    if start < inner.len() {
+        result.push(&inner[start..]);
+    }
+
+    result
+        .into_iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
 }
 
 /// Finds block in the specified BUILD file and executes `block_fn` for each founded blocks.
@@ -144,74 +565,196 @@ pub fn add_deps(
 ///
 /// * `build_file` - path to BUILD file
 /// * `block_start_fn` - marks that some block is started
-/// * `block_end_fn` - marks that some block is ended
 /// * `block_fn` - some action that will be executed when block is ended for each lines of this block
 /// * `skip_marker` - marker that prevent removing dependencies
+/// * `backup` - copy the original file to a sibling `BUILD.bak` before replacing it
+/// * `check` - don't write anything, just print a diff and report whether the file would change
 ///
-pub fn run_for_block<F1: FnMut(&str) -> bool, F2: FnMut(BTreeSet<String>) -> BTreeSet<String>>(
+/// The block is considered ended once the bracket opened on the `block_start_fn` line returns to
+/// depth zero, so nested brackets (list comprehensions, `[*a, *b]`, ...) and several named
+/// targets in a single BUILD file are all handled correctly. A block that opens and closes on
+/// the same line (e.g. `dependencies=["a:b"],`) is handled too, via `run_inline_block`.
+///
+/// Returns the number of entries the block grew (or shrank) by, and, if the file changed (or
+/// would change, in `check` mode), its path.
+pub fn run_for_block<F1: FnMut(&str) -> bool, F2: FnMut(DepsBlock) -> DepsBlock>(
     build_file: PathBuf,
     mut block_start_fn: F1,
-    block_end_fn: fn(&str) -> bool,
     mut block_fn: F2,
     skip_marker: &str,
-) -> Result<i32, Box<dyn Error>> {
-    let file = BufReader::new(File::open(&build_file)?);
+    backup: bool,
+    check: bool,
+) -> Result<(i32, Option<PathBuf>), Box<dyn Error>> {
+    let original: Vec<String> = BufReader::new(File::open(&build_file)?)
+        .lines()
+        .collect::<Result<_, _>>()?;
 
-    let mut line_buffer: BTreeSet<String> = BTreeSet::new();
+    let mut block = DepsBlock::new();
+    let mut pending_comments: Vec<String> = Vec::new();
     let mut inside_block = false;
+    let mut bracket_depth = BracketDepth::new();
     let mut line_edited: i32 = 0;
 
-    let lines: Vec<String> = file
-        .lines()
+    let lines: Vec<String> = original
+        .iter()
+        .cloned()
         .filter_map(|line| {
-            match line {
-                Ok(line) if !inside_block && block_start_fn(&line) => {
-                    assert!(
-                        line_buffer.is_empty(),
-                        "Buffer should be empty, inner blocks isn't supported"
-                    );
-                    inside_block = true;
-                    Some(vec![line])
-                }
-                Ok(line) if inside_block && block_end_fn(&line) => {
-                    // reached block end, runs `block_fn`
-                    let mut result = block_fn(line_buffer.clone());
-                    line_edited = result.len() as i32 - line_buffer.len() as i32;
-                    line_buffer.clear();
-                    result.insert(line);
+            if !inside_block && block_start_fn(&line) {
+                inside_block = true;
+                bracket_depth = BracketDepth::new();
+                if bracket_depth.feed_line(&line) <= 0 {
+                    // opened and closed on the same line, e.g. `dependencies=["a:b"]` - parse
+                    // any inline entries instead of leaving the line untouched
                     inside_block = false;
-                    Some(result.into_iter().collect())
-                }
-                Ok(line) if inside_block => {
-                    // inside a block, accumulate lines into buffer
-                    if line.ends_with(',') || line.contains(skip_marker) {
-                        line_buffer.insert(line.replace('"', "'"));
-                    } else if !line.is_empty() {
-                        line_buffer.insert(line.replace('"', "'") + ",");
-                    };
-                    None
-                }
-                Ok(line) => {
-                    // other lines just ignore
+                    Some(run_inline_block(&line, &mut block_fn, &mut line_edited))
+                } else {
                     Some(vec![line])
                 }
-                Err(err) => {
-                    println!("Error reading BUILD file {:?}: {:?}", build_file, err);
-                    None
-                }
+            } else if inside_block && bracket_depth.feed_line(&line) <= 0 {
+                // the bracket that opened the block has now closed: run `block_fn`
+                block.trailing_comments = std::mem::take(&mut pending_comments);
+                let before = block.len();
+                let result = block_fn(std::mem::take(&mut block));
+                line_edited = result.len() as i32 - before as i32;
+                let mut out = result.into_lines();
+                inside_block = false;
+                out.push(line);
+                Some(out)
+            } else if inside_block {
+                // inside a block: glue standalone comment lines to the dependency line that
+                // follows them, so sorting/filtering never separates documentation from target
+                if line.trim_start().starts_with('#') {
+                    pending_comments.push(line.replace('"', "'"));
+                } else if line.ends_with(',') || line.contains(skip_marker) {
+                    block.entries.push(DepEntry {
+                        leading_comments: std::mem::take(&mut pending_comments),
+                        dep_line: line.replace('"', "'"),
+                    });
+                } else if !line.is_empty() {
+                    block.entries.push(DepEntry {
+                        leading_comments: std::mem::take(&mut pending_comments),
+                        dep_line: line.replace('"', "'") + ",",
+                    });
+                };
+                None
+            } else {
+                // other lines just ignore
+                Some(vec![line])
             }
         })
         .flatten()
         .collect();
 
-    // write filtered dependencies back in BUILD file
-    let mut file = BufWriter::new(File::create(&build_file)?);
-    for line in lines {
-        writeln!(file, "{}", line)?;
+    if lines == original {
+        return Ok((line_edited, None));
+    }
+
+    if check {
+        print_build_diff(&build_file, &original, &lines);
+    } else {
+        // write filtered dependencies back in BUILD file atomically, so a crash or interruption
+        // mid-write can't leave the BUILD file truncated or half-rewritten
+        write_build_file(&build_file, &lines, backup)?;
+    }
+
+    Ok((line_edited, Some(build_file)))
+}
+
+/// Prints a unified diff of `original` vs `updated` lines for `build_file` (`-`/`+` per changed
+/// line, behind a per-file header), holding `STDOUT_LOCK` for the whole diff so it can't
+/// interleave with another file's diff or summary line from a concurrent rayon worker.
+fn print_build_diff(build_file: &PathBuf, original: &[String], updated: &[String]) {
+    let _guard = STDOUT_LOCK.lock().unwrap();
+
+    println!("--- a/{}", build_file.display());
+    println!("+++ b/{}", build_file.display());
+
+    let common = lcs_indices(original, updated);
+    let (mut oi, mut ui) = (0, 0);
+
+    for (co, cu) in common {
+        while oi < co {
+            println!("-{}", original[oi]);
+            oi += 1;
+        }
+        while ui < cu {
+            println!("+{}", updated[ui]);
+            ui += 1;
+        }
+        oi += 1;
+        ui += 1;
+    }
+    while oi < original.len() {
+        println!("-{}", original[oi]);
+        oi += 1;
+    }
+    while ui < updated.len() {
+        println!("+{}", updated[ui]);
+        ui += 1;
+    }
+}
+
+/// Indices `(i, j)` of the lines common to both sequences, in order, via a textbook
+/// dynamic-programming longest-common-subsequence.
+fn lcs_indices(a: &[String], b: &[String]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len(), b.len());
+    let mut dp = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            dp[i][j] = if a[i] == b[j] {
+                dp[i + 1][j + 1] + 1
+            } else {
+                dp[i + 1][j].max(dp[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            result.push((i, j));
+            i += 1;
+            j += 1;
+        } else if dp[i + 1][j] >= dp[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `lines` to `build_file` via a sibling temp file that is `fsync`'d and then renamed
+/// over the original, so readers never observe a partially-written BUILD file. If `backup` is
+/// set, the original contents are copied to a sibling `BUILD.bak` before the rename.
+fn write_build_file(build_file: &PathBuf, lines: &[String], backup: bool) -> Result<(), Box<dyn Error>> {
+    if backup {
+        fs::copy(build_file, build_file.with_extension("bak"))?;
     }
-    file.flush()?;
 
-    Ok(line_edited)
+    let file_name = build_file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("BUILD");
+    // The PID alone isn't enough to make this unique: several rayon worker threads in the same
+    // process can write BUILD files concurrently, so mix in a process-wide atomic counter too.
+    let unique = TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_path = build_file.with_file_name(format!("{}.tmp-{}-{}", file_name, process::id(), unique));
+
+    {
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+    }
+
+    fs::rename(&tmp_path, build_file)?;
+    Ok(())
 }
 
 const DEPS_START: &str = r"dependencies[\s]*=[\s]*\[";
@@ -228,14 +771,28 @@ pub fn exports_block_start(line: &str) -> bool {
     Regex::new(EXPORTS_START).unwrap().is_match(line)
 }
 
-#[inline]
-pub fn block_ends(line: &str) -> bool {
-    line.contains("]")
-}
-
 #[cfg(test)]
 mod tests {
-    use crate::sanitizer::deps_manager::{block_ends, deps_block_start, exports_block_start};
+    use crate::sanitizer::deps_manager::{
+        deps_block_start, exports_block_start, extract_address, lcs_indices,
+        levenshtein_distance, run_for_block, split_inline_block, split_inline_entries, DepEntry,
+        DepsBlock, TargetScope,
+    };
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write as _;
+    use std::process;
+
+    fn lines(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn entry(leading_comments: &[&str], dep_line: &str) -> DepEntry {
+        DepEntry {
+            leading_comments: lines(leading_comments),
+            dep_line: dep_line.to_string(),
+        }
+    }
 
     #[test]
     fn deps_block_start_test() {
@@ -265,13 +822,236 @@ mod tests {
     }
 
     #[test]
-    fn block_ends_test() {
-        assert!(!block_ends(""));
-        assert!(!block_ends("deps"));
-
-        assert!(block_ends("]"));
-        assert!(block_ends(" ]"));
-        assert!(block_ends("] "));
-        assert!(block_ends(" ] "));
+    fn split_inline_block_plain_dependencies_line() {
+        let (prefix, inner, suffix) =
+            split_inline_block(r#"    dependencies=["a:b", "c:d"],"#).unwrap();
+        assert_eq!(prefix, "    dependencies=[");
+        assert_eq!(inner, r#""a:b", "c:d""#);
+        assert_eq!(suffix, "],");
+    }
+
+    #[test]
+    fn split_inline_block_stops_at_the_deps_list_not_the_enclosing_call() {
+        // The enclosing `scala_library(...)` call opens a `(` before the `[` we care about, so
+        // the matching `]` must be found at the level just outside our own `[`, not at the
+        // first time the overall bracket depth returns to zero (which is the call's `)`).
+        let line = r#"scala_library(name="oneline", dependencies=["src/scala/d:d", "src/scala/a:a"])"#;
+        let (prefix, inner, suffix) = split_inline_block(line).unwrap();
+        assert_eq!(prefix, r#"scala_library(name="oneline", dependencies=["#);
+        assert_eq!(inner, r#""src/scala/d:d", "src/scala/a:a""#);
+        assert_eq!(suffix, "])");
+    }
+
+    #[test]
+    fn split_inline_block_no_brackets_returns_none() {
+        assert!(split_inline_block("name=\"oneline\",").is_none());
+    }
+
+    #[test]
+    fn split_inline_entries_splits_on_top_level_commas() {
+        assert_eq!(
+            split_inline_entries(r#""src/scala/d:d", "src/scala/a:a""#),
+            vec![r#""src/scala/d:d""#, r#""src/scala/a:a""#]
+        );
+    }
+
+    #[test]
+    fn split_inline_entries_ignores_commas_inside_quotes() {
+        assert_eq!(
+            split_inline_entries(r#""a:b,c""#),
+            vec![r#""a:b,c""#]
+        );
+    }
+
+    #[test]
+    fn target_scope_does_not_leak_into_a_later_target_with_a_similar_name() {
+        // "cc" must not stay in scope once "cc-test" starts, even though "cc-test" contains
+        // "cc" as a substring.
+        let mut scope = TargetScope::new(false);
+        let lines = [
+            "scala_library(",
+            "    name=\"cc\",",
+            "    dependencies=[",
+            "        ':shared',",
+            "    ],",
+            ")",
+            "",
+            "scala_library(",
+            "    name=\"cc-test\",",
+            "    dependencies=[",
+            "        ':shared',",
+            "    ],",
+            ")",
+        ];
+
+        let inside: Vec<bool> = lines
+            .iter()
+            .map(|line| scope.feed_line(line, "cc"))
+            .collect();
+
+        assert!(inside[2], "cc's own dependencies=[ line should be in scope");
+        assert!(
+            !inside[9],
+            "cc-test's dependencies=[ line must not be treated as cc's"
+        );
+    }
+
+    #[test]
+    fn target_scope_is_simple_covers_the_single_target_in_the_file() {
+        let mut scope = TargetScope::new(true);
+        assert!(scope.feed_line("scala_library(", "foo"));
+        assert!(scope.feed_line("    name=\"foo\",", "foo"));
+        assert!(scope.feed_line("    dependencies=[", "foo"));
+    }
+
+    #[test]
+    fn matches_target_name_requires_exact_quoted_match() {
+        use crate::sanitizer::deps_manager::matches_target_name;
+
+        assert!(matches_target_name("    name=\"cc\",", "cc"));
+        assert!(matches_target_name("    name='cc',", "cc"));
+        assert!(!matches_target_name("    name=\"cc-test\",", "cc"));
+    }
+
+    #[test]
+    fn levenshtein_distance_test() {
+        assert_eq!(levenshtein_distance("", ""), 0);
+        assert_eq!(levenshtein_distance("foo", "foo"), 0);
+        assert_eq!(levenshtein_distance("foo", ""), 3);
+        assert_eq!(levenshtein_distance("", "foo"), 3);
+        assert_eq!(levenshtein_distance("kitten", "sitting"), 3);
+        assert_eq!(
+            levenshtein_distance("src/scala/foo:foo", "src/scala/foo:fooo"),
+            1
+        );
+    }
+
+    #[test]
+    fn extract_address_test() {
+        assert_eq!(extract_address("        'foo/bar:baz',"), Some("foo/bar:baz"));
+        assert_eq!(extract_address("        \"foo/bar:baz\","), Some("foo/bar:baz"));
+        assert_eq!(extract_address("        no_quotes_here,"), None);
+    }
+
+    #[test]
+    fn lcs_indices_identical_sequences() {
+        let a = lines(&["a", "b", "c"]);
+        assert_eq!(lcs_indices(&a, &a), vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn lcs_indices_finds_common_lines_around_an_insertion() {
+        let original = lines(&["a", "b", "c"]);
+        let updated = lines(&["a", "x", "b", "c"]);
+        assert_eq!(lcs_indices(&original, &updated), vec![(0, 0), (1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn lcs_indices_finds_common_lines_around_a_removal() {
+        let original = lines(&["a", "b", "c"]);
+        let updated = lines(&["a", "c"]);
+        assert_eq!(lcs_indices(&original, &updated), vec![(0, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn lcs_indices_no_overlap() {
+        let original = lines(&["a", "b"]);
+        let updated = lines(&["c", "d"]);
+        assert_eq!(lcs_indices(&original, &updated), Vec::new());
+    }
+
+    #[test]
+    fn deps_block_sorted_keeps_leading_comments_glued_to_their_entry() {
+        let block = DepsBlock {
+            entries: vec![
+                entry(&["# b's comment"], "'b',"),
+                entry(&[], "'a',"),
+            ],
+            trailing_comments: Vec::new(),
+        };
+
+        let sorted = block.sorted().into_lines();
+        assert_eq!(sorted, lines(&["'a',", "# b's comment", "'b',"]));
+    }
+
+    #[test]
+    fn deps_block_into_lines_emits_trailing_comments_last() {
+        let block = DepsBlock {
+            entries: vec![entry(&[], "'a',")],
+            trailing_comments: lines(&["# trailing separator"]),
+        };
+
+        assert_eq!(
+            block.into_lines(),
+            lines(&["'a',", "# trailing separator"])
+        );
+    }
+
+    #[test]
+    fn deps_block_extend_sorted_dedups_against_existing_entries() {
+        let block = DepsBlock {
+            entries: vec![entry(&[], "'a',")],
+            trailing_comments: Vec::new(),
+        };
+
+        let extended = block.extend_sorted(vec!["'a',".to_string(), "'b',".to_string()]);
+        assert_eq!(extended.into_lines(), lines(&["'a',", "'b',"]));
+    }
+
+    #[test]
+    fn run_for_block_glues_standalone_comments_to_the_entry_below_when_sorting() {
+        let path = std::env::temp_dir().join(format!(
+            "pants_dependency_sanitizer_test_BUILD_{}",
+            process::id()
+        ));
+        {
+            let mut file = File::create(&path).unwrap();
+            writeln!(file, "scala_library(").unwrap();
+            writeln!(file, "    name=\"foo\",").unwrap();
+            writeln!(file, "    dependencies=[").unwrap();
+            writeln!(file, "        'b:b',").unwrap();
+            writeln!(file, "        # comment for a").unwrap();
+            writeln!(file, "        'a:a',").unwrap();
+            writeln!(file, "    ],").unwrap();
+            writeln!(file, ")").unwrap();
+        }
+
+        let result = run_for_block(
+            path.clone(),
+            |line| deps_block_start(line),
+            |block: DepsBlock| block.sorted(),
+            "#skip-sanitize",
+            false,
+            false,
+        );
+
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        let comment_idx = lines
+            .iter()
+            .position(|l| l.contains("comment for a"))
+            .expect("comment line should survive the sort");
+        assert_eq!(
+            lines[comment_idx + 1].trim(),
+            "'a:a',",
+            "the comment must stay glued to the entry it documents after sorting"
+        );
+    }
+
+    #[test]
+    fn deps_block_extend_preserving_order_keeps_existing_entries_untouched() {
+        // 'z' sorts after 'a', but extend_preserving_order must not reorder it - only the
+        // genuinely new 'b' should be appended.
+        let block = DepsBlock {
+            entries: vec![entry(&[], "'z',"), entry(&[], "'a',")],
+            trailing_comments: Vec::new(),
+        };
+
+        let extended =
+            block.extend_preserving_order(vec!["'a',".to_string(), "'b',".to_string()]);
+        assert_eq!(extended.into_lines(), lines(&["'z',", "'a',", "'b',"]));
     }
 }
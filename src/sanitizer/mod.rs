@@ -1,10 +1,12 @@
 //! Provides functionality to optimizing Pants dependencies.
 
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Write};
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use rayon::prelude::*;
 use serde::de::DeserializeOwned;
 use serde_json;
 use walkdir::{DirEntry, WalkDir};
@@ -14,33 +16,157 @@ use crate::Command::{Sort, Undeclared, Unused};
 use crate::{Config, UndeclaredSubCommand, UnusedSubCommand};
 use std::env;
 use std::error::Error;
+use std::process;
 
 mod deps_manager;
 
 /// Perform Action corresponded to the Config.
+///
+/// In `--check` or `--dry-run` mode, nothing is written to disk; instead, a unified diff of what
+/// would have changed is printed per BUILD file. `--check` additionally exits with status 1, once
+/// every affected BUILD file has been scanned, if anything would have changed, so CI can gate on
+/// it; `--dry-run` always exits 0, for a plain preview.
 pub fn perform(config: Config) {
-    match config.cmd {
+    let preview = config.check || config.dry_run;
+
+    let (changed, summary) = match config.cmd {
         Unused { cmd } => match cmd {
-            UnusedSubCommand::Show => show_unused(config.report_file, config.prefix),
-            UnusedSubCommand::Fix => {
-                fix_unused(config.report_file, config.prefix, config.skip_marker)
+            UnusedSubCommand::Show => {
+                show_unused(config.report_file, config.pants_binary, config.prefix);
+                (Vec::new(), RunSummary::empty("unused-show"))
             }
+            UnusedSubCommand::Fix => fix_unused(
+                config.report_file,
+                config.pants_binary,
+                config.prefix,
+                config.skip_marker,
+                config.backup,
+                preview,
+                config.jobs,
+            ),
         },
         Undeclared { cmd } => match cmd {
-            UndeclaredSubCommand::Show => show_undeclared(config.report_file, config.prefix),
-            UndeclaredSubCommand::Fix => {
-                fix_undeclared(config.report_file, config.prefix, &config.skip_marker)
+            UndeclaredSubCommand::Show => {
+                show_undeclared(config.report_file, config.pants_binary, config.prefix);
+                (Vec::new(), RunSummary::empty("undeclared-show"))
             }
+            UndeclaredSubCommand::Fix => fix_undeclared(
+                config.report_file,
+                config.pants_binary,
+                config.prefix,
+                &config.skip_marker,
+                config.backup,
+                preview,
+                config.jobs,
+                config.preserve_order,
+            ),
         },
-        Sort {} => {
-            sort_recursively(config.prefix, &config.skip_marker).expect("Cant sort dependencies")
+        Sort {} => sort_recursively(
+            config.prefix,
+            &config.skip_marker,
+            config.backup,
+            preview,
+            config.jobs,
+        )
+        .expect("Cant sort dependencies"),
+    };
+
+    if let Some(summary_file) = &config.summary_file {
+        write_summary(summary_file, &summary, &config.summary_format)
+            .expect("Couldn't write summary file");
+    }
+
+    if config.check && !changed.is_empty() {
+        println!(
+            "{} BUILD file(s) are not sanitized:\n{}",
+            changed.len(),
+            changed
+                .iter()
+                .map(|path| format!("  {}", path.display()))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+        process::exit(1);
+    }
+}
+
+/// One module's worth of dependency changes from a run, for `--summary-file`.
+#[derive(Serialize)]
+struct ModuleSummary {
+    module: String,
+    addresses: Vec<String>,
+}
+
+/// A machine-readable record of one `perform` run, written to `--summary-file` as JSON or CSV so
+/// CI can track dependency-hygiene trends across commits instead of scraping console text.
+#[derive(Serialize)]
+struct RunSummary {
+    action: String,
+    timestamp: u64,
+    modules: Vec<ModuleSummary>,
+    total: usize,
+    changed_files: usize,
+}
+
+impl RunSummary {
+    fn empty(action: &str) -> Self {
+        RunSummary {
+            action: action.to_string(),
+            timestamp: now(),
+            modules: Vec::new(),
+            total: 0,
+            changed_files: 0,
+        }
+    }
+
+    fn new(action: &str, modules: Vec<ModuleSummary>, changed_files: usize) -> Self {
+        let total = modules.iter().map(|m| m.addresses.len()).sum();
+        RunSummary {
+            action: action.to_string(),
+            timestamp: now(),
+            modules,
+            total,
+            changed_files,
         }
     }
 }
 
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Writes `summary` to `summary_file` as JSON or CSV (one row per module, plus a final totals
+/// row), depending on `format` ("json"/"csv").
+fn write_summary(
+    summary_file: &PathBuf,
+    summary: &RunSummary,
+    format: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = File::create(summary_file)?;
+
+    if format == "csv" {
+        writeln!(file, "module,addresses")?;
+        for module in &summary.modules {
+            writeln!(file, "{},{}", module.module, module.addresses.join(";"))?;
+        }
+        writeln!(
+            file,
+            "TOTAL,action={} total={} changed_files={} timestamp={}",
+            summary.action, summary.total, summary.changed_files, summary.timestamp
+        )?;
+    } else {
+        serde_json::to_writer_pretty(file, summary)?;
+    }
+
+    Ok(())
+}
+
 /// Print report about all unused dependencies.
-fn show_unused(report: PathBuf, prefix: String) {
-    let unused = select(report, "unused", prefix);
+fn show_unused(report_file: Option<PathBuf>, pants_binary: String, prefix: String) {
+    let unused = select(report_file, &pants_binary, "unused", prefix);
     let modules = unused.len();
     let unused_amount: usize = unused.values().map(Vec::len).sum();
     println!(
@@ -49,19 +175,62 @@ fn show_unused(report: PathBuf, prefix: String) {
     );
 }
 
-/// Removes all unused dependencies from all corresponded BUILD files.
-fn fix_unused(report: PathBuf, prefix: String, skip_marker: String) {
-    let unused = select(report, "unused", prefix);
-    for (module, deps) in unused {
-        let removed = deps_manager::remove_deps(&module, &deps, &skip_marker)
-            .unwrap_or_else(|_| panic!("Couldn't remove unused for module: {:?}", module));
-        println!("{:?} removed: {}", module, removed)
-    }
+/// Removes all unused dependencies from all corresponded BUILD files, returning the paths of
+/// the BUILD files that changed (or would change, in `--check` mode) and a summary of what was
+/// removed per module. Modules are grouped by BUILD file and processed in parallel across groups
+/// via rayon, but serially within a group, so two targets that share a BUILD file (e.g. `foo:lib`
+/// and `foo:test`) never race to rewrite it.
+fn fix_unused(
+    report_file: Option<PathBuf>,
+    pants_binary: String,
+    prefix: String,
+    skip_marker: String,
+    backup: bool,
+    check: bool,
+    jobs: Option<usize>,
+) -> (Vec<PathBuf>, RunSummary) {
+    let unused = select(report_file, &pants_binary, "unused", prefix);
+    let groups = group_by_build_file(unused);
+    let results: Vec<(Vec<PathBuf>, Vec<ModuleSummary>)> = with_thread_pool(jobs, || {
+        groups
+            .into_par_iter()
+            .map(|(_, modules)| {
+                let mut files = Vec::new();
+                let mut module_summaries = Vec::new();
+                for (module, deps) in modules {
+                    let (removed, changed) =
+                        deps_manager::remove_deps(&module, &deps, &skip_marker, backup, check)
+                            .unwrap_or_else(|_| {
+                                panic!("Couldn't remove unused for module: {:?}", module)
+                            });
+                    {
+                        let _guard = deps_manager::STDOUT_LOCK.lock().unwrap();
+                        println!("{:?} removed: {}", module, removed);
+                    }
+                    files.extend(changed);
+                    if removed != 0 {
+                        module_summaries.push(ModuleSummary {
+                            module: module.as_str(),
+                            addresses: deps.iter().map(Address::as_str).collect(),
+                        });
+                    }
+                }
+                (files, module_summaries)
+            })
+            .collect()
+    });
+
+    let (file_lists, module_lists): (Vec<Vec<PathBuf>>, Vec<Vec<ModuleSummary>>) =
+        results.into_iter().unzip();
+    let files: Vec<PathBuf> = file_lists.into_iter().flatten().collect();
+    let modules: Vec<ModuleSummary> = module_lists.into_iter().flatten().collect();
+    let changed_files = files.len();
+    (files, RunSummary::new("unused-fix", modules, changed_files))
 }
 
 /// Print report about all undeclared dependencies.
-fn show_undeclared(report: PathBuf, prefix: String) {
-    let undeclared = select(report, "undeclared", prefix);
+fn show_undeclared(report_file: Option<PathBuf>, pants_binary: String, prefix: String) {
+    let undeclared = select(report_file, &pants_binary, "undeclared", prefix);
     let modules = undeclared.len();
     let undeclared_amount: usize = undeclared.values().map(Vec::len).sum();
     println!(
@@ -70,23 +239,91 @@ fn show_undeclared(report: PathBuf, prefix: String) {
     );
 }
 
-/// Add to corresponded BUILD files all undeclared but used transitively dependencies
-fn fix_undeclared(report: PathBuf, prefix: String, skip_marker: &str) {
-    let undeclared = select(report, "undeclared", prefix);
-    for (module, deps) in undeclared {
-        let added = deps_manager::add_deps(&module, deps, skip_marker)
-            .unwrap_or_else(|_| panic!("Couldn't add undeclared deps to the module: {:?}", module));
-        println!("{:?} added: {}", module, added)
+/// Add to corresponded BUILD files all undeclared but used transitively dependencies, returning
+/// the paths of the BUILD files that changed (or would change, in `--check` mode) and a summary
+/// of what was added per module. Mirrors `fix_unused`: calls `select` for the "undeclared"
+/// dependency type and writes each module's missing targets back via `deps_manager::add_deps`.
+/// Modules are grouped by BUILD file and processed in parallel across groups via rayon, but
+/// serially within a group, so two targets that share a BUILD file never race to rewrite it.
+fn fix_undeclared(
+    report_file: Option<PathBuf>,
+    pants_binary: String,
+    prefix: String,
+    skip_marker: &str,
+    backup: bool,
+    check: bool,
+    jobs: Option<usize>,
+    preserve_order: bool,
+) -> (Vec<PathBuf>, RunSummary) {
+    let undeclared = select(report_file, &pants_binary, "undeclared", prefix);
+    let groups = group_by_build_file(undeclared);
+    let results: Vec<(Vec<PathBuf>, Vec<ModuleSummary>)> = with_thread_pool(jobs, || {
+        groups
+            .into_par_iter()
+            .map(|(_, modules)| {
+                let mut files = Vec::new();
+                let mut module_summaries = Vec::new();
+                for (module, deps) in modules {
+                    let (added, changed) = deps_manager::add_deps(
+                        &module,
+                        deps.clone(),
+                        skip_marker,
+                        backup,
+                        check,
+                        preserve_order,
+                    )
+                    .unwrap_or_else(|_| {
+                        panic!("Couldn't add undeclared deps to the module: {:?}", module)
+                    });
+                    {
+                        let _guard = deps_manager::STDOUT_LOCK.lock().unwrap();
+                        println!("{:?} added: {}", module, added);
+                    }
+                    files.extend(changed);
+                    if added != 0 {
+                        module_summaries.push(ModuleSummary {
+                            module: module.as_str(),
+                            addresses: deps.iter().map(Address::as_str).collect(),
+                        });
+                    }
+                }
+                (files, module_summaries)
+            })
+            .collect()
+    });
+
+    let (file_lists, module_lists): (Vec<Vec<PathBuf>>, Vec<Vec<ModuleSummary>>) =
+        results.into_iter().unzip();
+    let files: Vec<PathBuf> = file_lists.into_iter().flatten().collect();
+    let modules: Vec<ModuleSummary> = module_lists.into_iter().flatten().collect();
+    let changed_files = files.len();
+    (
+        files,
+        RunSummary::new("undeclared-fix", modules, changed_files),
+    )
+}
+
+/// Groups modules by the folder holding their shared BUILD file, so callers can parallelize
+/// across distinct BUILD files while still processing modules that share one serially - two
+/// targets in the same folder (e.g. `foo:lib` and `foo:test`) would otherwise both read, edit
+/// and rename the same BUILD file concurrently, racing to a last-write-wins result.
+fn group_by_build_file<T>(modules: BTreeMap<Address, T>) -> Vec<(String, Vec<(Address, T)>)> {
+    let mut groups: BTreeMap<String, Vec<(Address, T)>> = BTreeMap::new();
+    for (module, value) in modules {
+        groups.entry(module.folder.clone()).or_default().push((module, value));
     }
+    groups.into_iter().collect()
 }
 
 /// Aggregates modules and their dependencies with specified type.
 fn select(
-    report: PathBuf,
+    report_file: Option<PathBuf>,
+    pants_binary: &str,
     dependency_type: &str,
     prefix: String,
 ) -> BTreeMap<Address, Vec<Address>> {
-    let json = read_report::<HashMap<String, Info>>(report).expect("Couldn't read as json");
+    let json = read_report::<HashMap<String, Info>>(report_file, pants_binary, &prefix)
+        .expect("Couldn't read as json");
     json.into_iter()
         .filter_map(|(module, info)| {
             if module.contains("3rdparty") || !module.starts_with(&prefix) {
@@ -115,34 +352,76 @@ fn select(
         .collect()
 }
 
-/** Finds all BUILD files recursively and sort dependencies. */
-fn sort_recursively(prefix: String, skip_marker: &str) -> Result<(), Box<dyn Error>> {
+/** Finds all BUILD files recursively and sort dependencies, returning the paths of the BUILD
+files that changed (or would change, in `--check` mode) and a summary of which ones sorted.
+BUILD files are collected up front and then sorted in parallel via rayon, since each one is read,
+transformed and written independently. */
+fn sort_recursively(
+    prefix: String,
+    skip_marker: &str,
+    backup: bool,
+    check: bool,
+    jobs: Option<usize>,
+) -> Result<(Vec<PathBuf>, RunSummary), Box<dyn Error>> {
     let mut current_dir = env::current_dir()?;
     current_dir.push(prefix);
 
-    WalkDir::new(current_dir).into_iter().for_each(|result| {
-        match result {
-            Ok(entry) if is_build_file(&entry) => {
-                println!("sorted {}", entry.path().display());
-                deps_manager::run_for_block(
-                    entry.clone().into_path(),
+    let build_files: Vec<PathBuf> = WalkDir::new(current_dir)
+        .into_iter()
+        .filter_map(|result| match result {
+            Ok(entry) if is_build_file(&entry) => Some(entry.into_path()),
+            _ => None,
+        })
+        .collect();
+
+    let changed: Vec<PathBuf> = with_thread_pool(jobs, || {
+        build_files
+            .into_par_iter()
+            .flat_map(|build_file| {
+                let (_, file) = deps_manager::run_for_block(
+                    build_file.clone(),
                     |line| {
                         deps_manager::deps_block_start(line)
                             || deps_manager::exports_block_start(line)
                     },
-                    deps_manager::block_ends,
-                    |set: BTreeSet<String>| set,
+                    |block: deps_manager::DepsBlock| block.sorted(),
                     skip_marker,
+                    backup,
+                    check,
                 )
-                .expect(&format!("Cant sort {:?}", entry));
-            }
-            _ => {
-                // skip any error
-            }
-        }
+                .expect(&format!("Cant sort {:?}", build_file));
+
+                let _guard = deps_manager::STDOUT_LOCK.lock().unwrap();
+                println!("sorted {}", build_file.display());
+
+                file
+            })
+            .collect()
     });
 
-    Ok(())
+    let modules = changed
+        .iter()
+        .map(|file| ModuleSummary {
+            module: file.display().to_string(),
+            addresses: Vec::new(),
+        })
+        .collect();
+    let changed_files = changed.len();
+    let summary = RunSummary::new("sort", modules, changed_files);
+    Ok((changed, summary))
+}
+
+/// Runs `f` inside a rayon thread pool bounded to `jobs` threads, or rayon's global pool
+/// (sized by its own CPU-count heuristic) when `jobs` is unset.
+fn with_thread_pool<T: Send, F: FnOnce() -> T + Send>(jobs: Option<usize>, f: F) -> T {
+    match jobs {
+        Some(jobs) => rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .expect("Couldn't build rayon thread pool")
+            .install(f),
+        None => f(),
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -178,12 +457,52 @@ pub struct Info {
     products_total: usize,
 }
 
-/// Try to read report json file
-pub fn read_report<T: DeserializeOwned>(report: PathBuf) -> Result<T, String> {
-    let file = File::open(&report)
-        .map_err(|e| format!("Couldn't open the file {:?}. Cause={}", &report, e))?;
-    serde_json::from_reader(BufReader::new(file))
-        .map_err(|e| format!("Couldn't parse json file {:?}. Cause = {}", &report, e))
+/// Reads the dependency report from `report_file` when given, for offline use; otherwise
+/// invokes `pants_binary` live against `{prefix}::` and parses its stdout, so the report always
+/// reflects the current state of the tree.
+pub fn read_report<T: DeserializeOwned>(
+    report_file: Option<PathBuf>,
+    pants_binary: &str,
+    prefix: &str,
+) -> Result<T, String> {
+    match report_file {
+        Some(report_file) => {
+            let file = File::open(&report_file)
+                .map_err(|e| format!("Couldn't open the file {:?}. Cause={}", &report_file, e))?;
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+                format!("Couldn't parse json file {:?}. Cause = {}", &report_file, e)
+            })
+        }
+        None => {
+            let target_spec = format!("{}::", prefix);
+            let output = process::Command::new(pants_binary)
+                .args(&["-q", "dep-usage.jvm", "--no-summary", &target_spec])
+                .output()
+                .map_err(|e| {
+                    format!(
+                        "Couldn't run '{} -q dep-usage.jvm --no-summary {}'. Cause={}",
+                        pants_binary, target_spec, e
+                    )
+                })?;
+
+            if !output.status.success() {
+                return Err(format!(
+                    "'{} -q dep-usage.jvm --no-summary {}' exited with {}: {}",
+                    pants_binary,
+                    target_spec,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+
+            serde_json::from_slice(&output.stdout).map_err(|e| {
+                format!(
+                    "Couldn't parse dep-usage.jvm output for {}. Cause = {}",
+                    target_spec, e
+                )
+            })
+        }
+    }
 }
 
 #[inline]